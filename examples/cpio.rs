@@ -0,0 +1,441 @@
+// A minimal `cpio`-like tool built on top of this crate.
+//
+//   cpio -o [-H newc] < name-list > archive   create an archive
+//   cpio -t < archive                         list its contents
+//   cpio -i < archive                         extract it into the current directory
+
+extern crate cpio;
+
+use cpio::newc::Magic;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+
+const USAGE: &str = "usage: cpio -o [-H newc] | -t | -i";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let status = run(
+        &args,
+        stdin.lock(),
+        stdout.lock(),
+        &mut io::stderr(),
+        Path::new("."),
+    );
+    process::exit(status);
+}
+
+/// Runs the tool with the arguments `args`, extracting into `dest`, and
+/// returns its exit status.
+fn run<R: BufRead, W: Write>(
+    args: &[String],
+    input: R,
+    output: W,
+    errors: &mut dyn Write,
+    dest: &Path,
+) -> i32 {
+    let mut mode = None;
+    let mut format = Magic::Newc;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "-t" | "-i" if mode.is_none() => mode = Some(arg.as_str()),
+            "-H" => match args.next().map(|format| format.parse()) {
+                Some(Ok(magic)) => format = magic,
+                Some(Err(err)) => {
+                    let _ = writeln!(errors, "cpio: {}", err);
+                    return 2;
+                }
+                None => mode = None,
+            },
+            _ => {
+                mode = None;
+                break;
+            }
+        }
+    }
+
+    let result = match (mode, format) {
+        // Only plain `newc` archives can be written; both kinds can be read.
+        (Some("-o"), Magic::Crc) => {
+            let _ = writeln!(errors, "cpio: writing crc archives isn't supported");
+            return 2;
+        }
+        (Some("-o"), _) => create(input, output),
+        (Some("-t"), _) => list(input, output),
+        (Some("-i"), _) => extract(input, dest),
+        _ => {
+            let _ = writeln!(errors, "{}", USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            let _ = writeln!(errors, "cpio: {}", err);
+            1
+        }
+    }
+}
+
+/// Writes an archive of the files named, one per line, in `names`.
+fn create<R: BufRead, W: Write>(names: R, mut output: W) -> io::Result<()> {
+    for (ino, name) in names.lines().enumerate() {
+        let name = name?;
+        if name.is_empty() {
+            continue;
+        }
+        let metadata = fs::symlink_metadata(&name)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", name, err)))?;
+        let mode = file_mode(&metadata);
+        let builder = cpio::NewcBuilder::new(&name)
+            .ino(ino as u32)
+            .mode(mode)
+            .nlink(if metadata.is_dir() { 2 } else { 1 })
+            .mtime(mtime(&metadata));
+
+        match mode & S_IFMT {
+            S_IFREG => {
+                let mut file = fs::File::open(&name)?;
+                let len = file.metadata()?.len();
+                if len > u64::from(u32::MAX) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}: too large for the newc format", name),
+                    ));
+                }
+                let mut writer = builder.write(&mut output, len as u32);
+                io::copy(&mut file, &mut writer)?;
+                writer.finish()?;
+            }
+            // Symlinks store their target as the entry data.
+            S_IFLNK => {
+                let target = fs::read_link(&name)?.to_string_lossy().into_owned();
+                let mut writer = builder.write(&mut output, target.len() as u32);
+                writer.write_all(target.as_bytes())?;
+                writer.finish()?;
+            }
+            _ => {
+                builder.write(&mut output, 0).finish()?;
+            }
+        }
+    }
+    cpio::newc::trailer(&mut output)?;
+    output.flush()
+}
+
+/// Prints the name of every entry in `archive`.
+fn list<R: Read, W: Write>(archive: R, mut output: W) -> io::Result<()> {
+    cpio::for_each_entry(archive, |entry, _| writeln!(output, "{}", entry.name()))
+}
+
+/// Extracts the regular files, directories and symlinks in `archive` under
+/// `dest`, skipping anything else.
+///
+/// Like GNU cpio, hard links are recognised by their shared device and inode
+/// numbers, with the data stored only on the last entry of each group.
+fn extract<R: Read>(archive: R, dest: &Path) -> io::Result<()> {
+    // Names of hard links still waiting for the entry that carries their data.
+    let mut links: HashMap<(u32, u32, u32), Vec<String>> = HashMap::new();
+    cpio::for_each_entry(archive, |entry, data| {
+        let path = dest_path(dest, entry.name())?;
+        match entry.mode() & S_IFMT {
+            S_IFDIR => fs::create_dir_all(&path),
+            S_IFREG => {
+                let key = (entry.dev_major(), entry.dev_minor(), entry.ino());
+                if entry.nlink() > 1 && entry.file_size() == 0 {
+                    links.entry(key).or_default().push(entry.name().to_string());
+                    return Ok(());
+                }
+                create_file(&path, data)?;
+                if entry.nlink() > 1 {
+                    for name in links.remove(&key).unwrap_or_default() {
+                        hard_link(&path, dest, &name)?;
+                    }
+                }
+                Ok(())
+            }
+            S_IFLNK => {
+                let mut target = String::new();
+                data.read_to_string(&mut target)?;
+                symlink(&target, &path)
+            }
+            _ => {
+                eprintln!("cpio: {}: skipping unsupported file type", entry.name());
+                Ok(())
+            }
+        }
+    })?;
+
+    // Links that never got any data are all the same empty file.
+    for (_, names) in links {
+        let path = dest_path(dest, &names[0])?;
+        create_file(&path, &mut io::empty())?;
+        for name in &names[1..] {
+            hard_link(&path, dest, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns where to extract the entry called `name` under `dest`, refusing
+/// names that would end up outside of it.
+fn dest_path(dest: &Path, name: &str) -> io::Result<PathBuf> {
+    let refuse = |why: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: refusing to extract {}", name, why),
+        )
+    };
+    let mut path = dest.to_owned();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(component) => path.push(component),
+            Component::CurDir => continue,
+            _ => return Err(refuse("outside the destination")),
+        }
+        // Refuse to follow a symlink extracted earlier out of `dest`.
+        let is_symlink = fs::symlink_metadata(&path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_symlink {
+            return Err(refuse("through a symlink"));
+        }
+    }
+    Ok(path)
+}
+
+fn create_file(path: &Path, data: &mut dyn Read) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::File::create(path)?;
+    io::copy(data, &mut file)?;
+    Ok(())
+}
+
+/// Links the entry called `name` under `dest` to the file at `original`.
+fn hard_link(original: &Path, dest: &Path, name: &str) -> io::Result<()> {
+    // Checked again now, in case a symlink was extracted in the meantime.
+    let path = dest_path(dest, name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    fs::hard_link(original, &path)
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mode()
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        S_IFDIR | 0o755
+    } else if metadata.file_type().is_symlink() {
+        S_IFLNK | 0o777
+    } else {
+        S_IFREG | 0o644
+    }
+}
+
+#[cfg(unix)]
+fn mtime(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mtime() as u32
+}
+
+#[cfg(not(unix))]
+fn mtime(_metadata: &fs::Metadata) -> u32 {
+    0
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, path: &Path) -> io::Result<()> {
+    eprintln!("cpio: {}: skipping symlink", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty scratch directory unique to this process and `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cpio-example-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs the tool on `input`, returning its exit status, output and
+    /// error messages.
+    fn cpio(args: &[&str], input: &[u8], dest: &Path) -> (i32, Vec<u8>, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut output = vec![];
+        let mut errors = vec![];
+        let status = run(&args, input, &mut output, &mut errors, dest);
+        (status, output, String::from_utf8(errors).unwrap())
+    }
+
+    #[test]
+    fn test_create_list_extract() {
+        let src = scratch_dir("src");
+        fs::create_dir(src.join("dir")).unwrap();
+        fs::write(src.join("dir/hello"), "Hello, World").unwrap();
+        fs::write(src.join("empty"), "").unwrap();
+
+        let names: Vec<String> = ["dir", "dir/hello", "empty"]
+            .iter()
+            .map(|name| src.join(name).to_string_lossy().into_owned())
+            .collect();
+        let (status, archive, _) = cpio(&["-o", "-H", "newc"], names.join("\n").as_bytes(), &src);
+        assert_eq!(status, 0);
+
+        let (status, listing, _) = cpio(&["-t"], &archive, &src);
+        assert_eq!(status, 0);
+        assert_eq!(String::from_utf8(listing).unwrap(), names.join("\n") + "\n");
+
+        // Extract a copy of the archive with relative names.
+        let mut relative = vec![];
+        cpio::ArchiveRewriter::new(archive.as_slice(), &mut relative)
+            .rewrite(|entry, _| {
+                let name = Path::new(entry.name())
+                    .strip_prefix(&src)
+                    .unwrap()
+                    .to_owned();
+                entry.set_name(&name.to_string_lossy());
+                cpio::Action::Replace
+            })
+            .unwrap();
+        let dest = scratch_dir("dest");
+        assert_eq!(cpio(&["-i"], &relative, &dest).0, 0);
+        assert!(dest.join("dir").is_dir());
+        assert_eq!(fs::read(dest.join("dir/hello")).unwrap(), b"Hello, World");
+        assert_eq!(fs::read(dest.join("empty")).unwrap(), b"");
+
+        // The original absolute names are refused.
+        let (status, _, errors) = cpio(&["-i"], &archive, &dest);
+        assert_eq!(status, 1);
+        assert!(errors.ends_with("refusing to extract outside the destination\n"));
+
+        // Entries can't escape through a symlink extracted earlier.
+        let outside = scratch_dir("outside");
+        let target = outside.to_string_lossy().into_owned();
+        let mut archive = vec![];
+        let mut writer = cpio::NewcBuilder::new("ab")
+            .mode(S_IFLNK | 0o777)
+            .write(archive, target.len() as u32);
+        writer.write_all(target.as_bytes()).unwrap();
+        archive = writer.finish().unwrap();
+        let mut writer = cpio::NewcBuilder::new("ab/x")
+            .mode(S_IFREG | 0o644)
+            .write(archive, 4);
+        writer.write_all(b"oops").unwrap();
+        archive = cpio::newc::trailer(writer.finish().unwrap()).unwrap();
+
+        let linked = scratch_dir("linked");
+        let err = extract(archive.as_slice(), &linked).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!outside.join("x").exists());
+
+        // Nor is an existing symlink overwritten.
+        let err = extract(archive.as_slice(), &linked).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read_link(linked.join("ab")).unwrap(), outside);
+
+        fs::remove_dir_all(&src).unwrap();
+        fs::remove_dir_all(&dest).unwrap();
+        fs::remove_dir_all(&linked).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        let dest = env::temp_dir();
+        let usage = format!("{}\n", USAGE);
+        for args in [
+            &[][..],
+            &["-x"],
+            &["-o", "-t"],
+            &["-H", "newc"],
+            &["-o", "-H"],
+        ]
+        .iter()
+        {
+            let (status, output, errors) = cpio(args, b"", &dest);
+            assert_eq!(status, 2, "{:?}", args);
+            assert!(output.is_empty());
+            assert_eq!(errors, usage);
+        }
+
+        let (status, _, errors) = cpio(&["-o", "-H", "tar"], b"", &dest);
+        assert_eq!(status, 2);
+        assert_eq!(errors, "cpio: unsupported archive format `tar`\n");
+        let (status, _, errors) = cpio(&["-o", "-H", "crc"], b"", &dest);
+        assert_eq!(status, 2);
+        assert_eq!(errors, "cpio: writing crc archives isn't supported\n");
+
+        // Reading isn't tied to `-H`, but a non-archive is still an error.
+        let (status, _, errors) = cpio(&["-t", "-H", "crc"], b"not an archive", &dest);
+        assert_eq!(status, 1);
+        assert_eq!(errors, "cpio: Invalid magic number\n");
+    }
+
+    #[test]
+    fn test_extract_hard_links() {
+        // As GNU cpio writes them: `a` and `b` share `b`'s data, while `c` and
+        // `d` are an empty file.
+        let mut archive = vec![];
+        for &(name, ino, data) in [
+            ("a", 7, &b""[..]),
+            ("c", 8, b""),
+            ("b", 7, b"Hello"),
+            ("d", 8, b""),
+        ]
+        .iter()
+        {
+            let mut writer = cpio::NewcBuilder::new(name)
+                .ino(ino)
+                .mode(S_IFREG | 0o644)
+                .nlink(2)
+                .write(archive, data.len() as u32);
+            writer.write_all(data).unwrap();
+            archive = writer.finish().unwrap();
+        }
+        let archive = cpio::newc::trailer(archive).unwrap();
+
+        let dest = scratch_dir("links");
+        extract(archive.as_slice(), &dest).unwrap();
+        assert_eq!(fs::read(dest.join("a")).unwrap(), b"Hello");
+        assert_eq!(fs::read(dest.join("b")).unwrap(), b"Hello");
+        assert_eq!(fs::read(dest.join("c")).unwrap(), b"");
+        assert_eq!(fs::read(dest.join("d")).unwrap(), b"");
+
+        // Writing to one link shows through the other.
+        fs::write(dest.join("c"), "changed").unwrap();
+        assert_eq!(fs::read(dest.join("d")).unwrap(), b"changed");
+
+        fs::remove_dir_all(&dest).unwrap();
+    }
+}
@@ -3,13 +3,17 @@
 //!
 //! CPIO archives can be in any of several
 //! [formats](https://www.gnu.org/software/cpio/manual/cpio.html#format).  For
-//! now, this library only supports the `newc` (SVR4) format.
+//! now, this library only supports the `newc` (SVR4) format, and reading its
+//! `crc` variant.
 
-use std::io;
+#![cfg_attr(feature = "unstable", feature(test))]
+
+use std::io::{self, Read, Write};
 use std::iter::Iterator;
 
 pub mod newc;
 pub use newc::Builder as NewcBuilder;
+pub use newc::Entry as NewcEntry;
 pub use newc::Reader as NewcReader;
 
 /// Creates a new CPIO archive.
@@ -21,27 +25,151 @@ where
 {
     let output = inputs
         .enumerate()
-        .fold(Ok(output), |output, (idx, (builder, mut input))| {
-            // If the output is valid, try to write the next input file
-            output.and_then(move |output| {
-                // Grab the length of the input file
-                let len = input.seek(io::SeekFrom::End(0))?;
-                input.seek(io::SeekFrom::Start(0))?;
+        .try_fold(output, |output, (idx, (builder, mut input))| {
+            // Grab the length of the input file
+            let len = input.seek(io::SeekFrom::End(0))?;
+            input.seek(io::SeekFrom::Start(0))?;
 
-                // Create our writer fp with a unique inode number
-                let mut fp = builder.ino(idx as u32).write(output, len as u32);
+            // Create our writer fp with a unique inode number
+            let mut fp = builder.ino(idx as u32).write(output, len as u32);
 
-                // Write out the file
-                io::copy(&mut input, &mut fp)?;
+            // Write out the file
+            io::copy(&mut input, &mut fp)?;
 
-                // And finish off the input file
-                fp.finish()
-            })
+            // And finish off the input file
+            fp.finish()
         })?;
 
     newc::trailer(output)
 }
 
+/// Reads every entry of an archive up to its trailer, calling `f` with each
+/// entry's metadata and a reader for its contents.
+///
+/// The contents reader is bounded to the entry, and anything `f` leaves
+/// unread is skipped before moving on to the next entry.
+pub fn for_each_entry<R, F>(mut input: R, mut f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(NewcEntry, &mut dyn Read) -> io::Result<()>,
+{
+    loop {
+        let mut reader = NewcReader::new(input)?;
+        if reader.entry().is_trailer() {
+            return Ok(());
+        }
+        let entry = reader.entry().clone();
+        f(entry, &mut reader)?;
+        input = reader.finish()?;
+    }
+}
+
+/// What [`ArchiveRewriter::rewrite`] should do with an entry.
+pub enum Action {
+    /// Copy the entry exactly as it was read, ignoring any changes the
+    /// callback made to its metadata or contents. Only the padding is
+    /// rewritten, with NUL bytes.
+    Keep,
+    /// Write the entry using the (possibly modified) metadata and contents.
+    Replace,
+    /// Leave the entry out of the output archive.
+    Drop,
+}
+
+/// Copies an archive from `R` to `W`, letting the caller modify or remove
+/// entries along the way.
+pub struct ArchiveRewriter<R: Read, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: Read, W: Write> ArchiveRewriter<R, W> {
+    /// Creates a rewriter reading an archive from `input` and writing the
+    /// result to `output`.
+    pub fn new(input: R, output: W) -> ArchiveRewriter<R, W> {
+        ArchiveRewriter { input, output }
+    }
+
+    /// Rewrites every entry of the input archive, followed by a new trailer.
+    ///
+    /// The callback is handed each entry's metadata and contents, and returns
+    /// the [`Action`] to take for it. When replacing an entry, its file size
+    /// is taken from the contents.
+    ///
+    /// Only `newc` archives are supported: entries in the `crc` format are
+    /// an error, since the output can't carry their checksums.
+    pub fn rewrite<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&mut NewcEntry, &mut Vec<u8>) -> Action,
+    {
+        // Both buffers are reused across entries, so that keeping the original
+        // contents around costs a copy but not an allocation per entry.
+        let mut contents = vec![];
+        let mut data = vec![];
+        loop {
+            let mut reader = NewcReader::new(&mut self.input)?;
+            if reader.entry().is_trailer() {
+                reader.finish()?;
+                break;
+            }
+            if reader.entry().check().is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "ArchiveRewriter only supports newc archives",
+                ));
+            }
+
+            let original = reader.entry().clone();
+            contents.clear();
+            reader.read_to_end(&mut contents)?;
+            reader.finish()?;
+
+            let mut entry = original.clone();
+            data.clone_from(&contents);
+            match f(&mut entry, &mut data) {
+                Action::Keep => {
+                    keep_entry(&mut self.output, &original, &contents)?;
+                }
+                Action::Replace => {
+                    write_entry(&mut self.output, &entry, &data)?;
+                }
+                Action::Drop => {}
+            }
+        }
+
+        newc::trailer(&mut self.output)?;
+        self.output.flush()
+    }
+
+    /// Returns the underlying reader and writer.
+    pub fn into_inner(self) -> (R, W) {
+        (self.input, self.output)
+    }
+}
+
+fn keep_entry<W: Write>(output: W, entry: &NewcEntry, data: &[u8]) -> io::Result<W> {
+    match entry.raw_header() {
+        Some(header) => {
+            let mut name = entry.name().as_bytes().to_vec();
+            name.push(0);
+            newc::raw_entry(output, header, &name, data)
+        }
+        None => write_entry(output, entry, data),
+    }
+}
+
+fn write_entry<W: Write>(output: W, entry: &NewcEntry, data: &[u8]) -> io::Result<W> {
+    if data.len() > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Entry contents are too large for the newc format",
+        ));
+    }
+    let mut fp = entry.to_builder().write(output, data.len() as u32);
+    fp.write_all(data)?;
+    fp.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,4 +201,125 @@ mod tests {
         // Write out the CPIO archive
         let _ = write_cpio(input.drain(..), output).unwrap();
     }
+
+    #[test]
+    fn test_for_each_entry() {
+        let input = vec![
+            (NewcBuilder::new("./a").mode(0o100644), Cursor::new("aaa")),
+            (
+                NewcBuilder::new("./b").mode(0o100644),
+                Cursor::new("bbbbbb"),
+            ),
+        ];
+        let archive = write_cpio(input.into_iter(), vec![]).unwrap();
+
+        let mut found = vec![];
+        for_each_entry(archive.as_slice(), |entry, data| {
+            // Only read part of the second entry.
+            let mut contents = vec![];
+            data.take(3).read_to_end(&mut contents)?;
+            found.push((entry.name().to_string(), entry.file_size(), contents));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            found,
+            vec![
+                ("./a".to_string(), 3, b"aaa".to_vec()),
+                ("./b".to_string(), 6, b"bbb".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite() {
+        // Set up an archive with three files
+        let input = vec![
+            (NewcBuilder::new("./a").mode(0o100644), Cursor::new("aaa")),
+            (NewcBuilder::new("./b").mode(0o100644), Cursor::new("bbbb")),
+            (NewcBuilder::new("./c").mode(0o100644), Cursor::new("cc")),
+        ];
+        let archive = write_cpio(input.into_iter(), vec![]).unwrap();
+
+        // Drop `./b` and rename `./c`
+        let mut rewriter = ArchiveRewriter::new(archive.as_slice(), vec![]);
+        rewriter
+            .rewrite(|entry, _data| match entry.name() {
+                "./b" => Action::Drop,
+                "./c" => {
+                    entry.set_name("./renamed");
+                    Action::Replace
+                }
+                _ => Action::Keep,
+            })
+            .unwrap();
+        let (_, output) = rewriter.into_inner();
+
+        // Read the result back in
+        let mut found = vec![];
+        let mut file = output.as_slice();
+        loop {
+            let mut reader = NewcReader::new(file).unwrap();
+            if reader.entry().is_trailer() {
+                break;
+            }
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).unwrap();
+            found.push((reader.entry().name().to_string(), contents));
+            file = reader.finish().unwrap();
+        }
+        assert_eq!(
+            found,
+            vec![
+                ("./a".to_string(), "aaa".to_string()),
+                ("./renamed".to_string(), "cc".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_keep() {
+        let input = vec![(NewcBuilder::new("./a").mode(0o100644), Cursor::new("aaa"))];
+        let archive = write_cpio(input.into_iter(), vec![]).unwrap();
+
+        // Changes to the metadata and contents are dropped when keeping an
+        // entry.
+        let mut output = vec![];
+        ArchiveRewriter::new(archive.as_slice(), &mut output)
+            .rewrite(|entry, data| {
+                entry.set_name("./ignored");
+                entry.set_uid(5);
+                data.make_ascii_uppercase();
+                data.push(b'a');
+                Action::Keep
+            })
+            .unwrap();
+        assert_eq!(output, archive);
+
+        // Kept headers are copied byte for byte, so upper-case hex survives.
+        let header = String::from_utf8(archive[..110].to_vec())
+            .unwrap()
+            .to_uppercase();
+        let mut upper = newc::raw_entry(vec![], header.as_bytes(), b"./a\0", b"aaa").unwrap();
+        newc::trailer(&mut upper).unwrap();
+        let mut output = vec![];
+        ArchiveRewriter::new(upper.as_slice(), &mut output)
+            .rewrite(|_entry, _data| Action::Keep)
+            .unwrap();
+        assert_eq!(output[..upper.len()], upper[..]);
+    }
+
+    #[test]
+    fn test_rewrite_crc() {
+        // Turn a newc archive into a crc one; the check of "aaa" is 0x123.
+        let input = vec![(NewcBuilder::new("./a").mode(0o100644), Cursor::new("aaa"))];
+        let mut archive = write_cpio(input.into_iter(), vec![]).unwrap();
+        archive[..6].copy_from_slice(b"070702");
+        archive[102..110].copy_from_slice(b"00000123");
+
+        let err = ArchiveRewriter::new(archive.as_slice(), vec![])
+            .rewrite(|_entry, _data| Action::Keep)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
 }
@@ -1,14 +1,39 @@
 //! Read/write `newc` (SVR4) format archives.
+//!
+//! Archives in the `crc` variant of the format, which differs only in its
+//! magic number and in carrying a checksum of each file's data, can also be
+//! read.
 
-use std::io::{self, Read, Write};
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::str::FromStr;
 
 const HEADER_LEN: usize = 110;
 
 const MAGIC_NUMBER: &[u8] = b"070701";
 
+const CRC_MAGIC_NUMBER: &[u8] = b"070702";
+
 const TRAILER_NAME: &str = "TRAILER!!!";
 
+// File type bits of `c_mode`.
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
 /// Metadata about one entry from an archive.
+///
+/// Entries are ordered by name, with the remaining metadata (in declaration
+/// order) as tiebreakers, so sorting them gives a deterministic order. The
+/// raw header bytes kept for [`Entry::raw_field`] take no part in comparisons.
+#[derive(Clone, Debug)]
 pub struct Entry {
     name: String,
     ino: u32,
@@ -22,6 +47,133 @@ pub struct Entry {
     dev_minor: u32,
     rdev_major: u32,
     rdev_minor: u32,
+    check: Option<u32>,
+    raw: Option<[u8; HEADER_LEN]>,
+}
+
+impl Entry {
+    /// Returns the fields that take part in comparisons, in order.
+    fn key(&self) -> (&str, [u32; 11], Option<u32>) {
+        let fields = [
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.nlink,
+            self.mtime,
+            self.file_size,
+            self.dev_major,
+            self.dev_minor,
+            self.rdev_major,
+            self.rdev_minor,
+        ];
+        (&self.name, fields, self.check)
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// One of the fields of a `newc` header, following the magic number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// `c_ino`
+    Ino,
+    /// `c_mode`
+    Mode,
+    /// `c_uid`
+    Uid,
+    /// `c_gid`
+    Gid,
+    /// `c_nlink`
+    Nlink,
+    /// `c_mtime`
+    Mtime,
+    /// `c_filesize`
+    FileSize,
+    /// `c_devmajor`
+    DevMajor,
+    /// `c_devminor`
+    DevMinor,
+    /// `c_rdevmajor`
+    RdevMajor,
+    /// `c_rdevminor`
+    RdevMinor,
+    /// `c_namesize`
+    NameSize,
+    /// `c_check`
+    Check,
+}
+
+impl Field {
+    /// Returns the position of this field within the header.
+    fn range(self) -> ::std::ops::Range<usize> {
+        let start = MAGIC_NUMBER.len() + 8 * self as usize;
+        start..start + 8
+    }
+}
+
+/// The error returned when the underlying writer fails while writing an
+/// entry, naming the entry and the part of it being written.
+///
+/// It is carried inside an `io::Error` of the same kind as the original
+/// error, which is available as its `source`.
+#[derive(Debug)]
+pub struct WriteError {
+    name: String,
+    phase: &'static str,
+    source: io::Error,
+}
+
+impl WriteError {
+    /// Returns the name of the entry being written.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the part of the entry being written: `header`, `name`, `data`
+    /// or `padding`, or `flush` if flushing the writer after the entry's
+    /// final padding failed.
+    pub fn phase(&self) -> &str {
+        self.phase
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.phase == "flush" {
+            write!(f, "failed to flush `{}`: {}", self.name, self.source)
+        } else {
+            write!(
+                f,
+                "failed to write {} of `{}`: {}",
+                self.phase, self.name, self.source
+            )
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
 }
 
 /// Reads one entry header/data from an archive.
@@ -44,41 +196,298 @@ pub struct Builder {
     dev_minor: u32,
     rdev_major: u32,
     rdev_minor: u32,
+    padding_byte: u8,
 }
 
 /// Writes one entry header/data into an archive.
 pub struct Writer<W: Write> {
     inner: W,
+    name: String,
+    padding_byte: u8,
     written: u32,
     file_size: u32,
     header_size: usize,
     header: Vec<u8>,
 }
 
-fn pad(len: usize) -> Option<Vec<u8>> {
+fn pad(len: usize, byte: u8) -> Option<Vec<u8>> {
     // pad out to a multiple of 4 bytes
     let overhang = len % 4;
     if overhang != 0 {
         let repeat = 4 - overhang;
-        Some(vec![0u8; repeat])
+        Some(vec![byte; repeat])
     } else {
         None
     }
 }
 
-fn read_hex_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
-    let mut bytes = [0u8; 8];
-    reader.read_exact(&mut bytes)?;
-    ::std::str::from_utf8(&bytes)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid utf-8 header field"))
-        .and_then(|string| {
-            u32::from_str_radix(string, 16).map_err(|_| {
-                io::Error::new(io::ErrorKind::InvalidData, "Invalid hex u32 header field")
+/// Maps each byte to its hex digit value, or to `INVALID_HEX` if it isn't one.
+static HEX_DIGITS: [u8; 256] = hex_digits();
+
+const INVALID_HEX: u8 = 0xff;
+
+const fn hex_digits() -> [u8; 256] {
+    let mut table = [INVALID_HEX; 256];
+    let mut i = 0;
+    while i < 10 {
+        table[b'0' as usize + i] = i as u8;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 6 {
+        table[b'a' as usize + i] = 10 + i as u8;
+        table[b'A' as usize + i] = 10 + i as u8;
+        i += 1;
+    }
+    table
+}
+
+/// Decodes an 8-digit hex header field, in either upper or lower case.
+///
+/// Like GNU cpio, leading spaces are accepted in place of zeros, as long as
+/// at least one digit follows them.
+pub fn decode_hex8(bytes: &[u8; 8]) -> io::Result<u32> {
+    let padding = bytes.iter().take_while(|&&byte| byte == b' ').count();
+
+    // Accumulate without branching on each digit; any invalid byte sets the
+    // high bit of `invalid`.
+    let mut value = 0u32;
+    let mut invalid = if padding == bytes.len() {
+        INVALID_HEX
+    } else {
+        0
+    };
+    for &byte in &bytes[padding..] {
+        let digit = HEX_DIGITS[byte as usize];
+        invalid |= digit;
+        value = (value << 4) | u32::from(digit & 0xf);
+    }
+    if invalid & 0x80 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid hex u32 header field",
+        ));
+    }
+    Ok(value)
+}
+
+/// The magic number at the start of an entry header, which says which
+/// variant of the format the entry is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Magic {
+    /// `070701`, plain `newc`.
+    Newc,
+    /// `070702`, `newc` with a checksum of the file's data.
+    Crc,
+}
+
+impl Magic {
+    fn bytes(self) -> &'static [u8] {
+        match self {
+            Magic::Newc => MAGIC_NUMBER,
+            Magic::Crc => CRC_MAGIC_NUMBER,
+        }
+    }
+}
+
+impl FromStr for Magic {
+    type Err = ParseMagicError;
+
+    /// Parses the name GNU cpio's `-H` option uses for a format: `newc` or
+    /// `sv4cpio` for plain `newc`, and `crc` or `sv4crc` for `crc`. Like GNU
+    /// cpio, case is ignored.
+    fn from_str(s: &str) -> Result<Magic, ParseMagicError> {
+        let names = [
+            ("newc", Magic::Newc),
+            ("sv4cpio", Magic::Newc),
+            ("crc", Magic::Crc),
+            ("sv4crc", Magic::Crc),
+        ];
+        names
+            .iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, magic)| magic)
+            .ok_or_else(|| ParseMagicError {
+                name: s.to_string(),
             })
-        })
+    }
+}
+
+/// The error returned when parsing an unknown format name as a [`Magic`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseMagicError {
+    name: String,
+}
+
+impl fmt::Display for ParseMagicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported archive format `{}`", self.name)
+    }
+}
+
+impl error::Error for ParseMagicError {}
+
+/// Reads the magic number at the start of an entry header, failing if it
+/// isn't the `newc` or `crc` one.
+pub fn read_magic<R: Read>(reader: &mut R) -> io::Result<Magic> {
+    // char    c_magic[6];
+    let mut magic = [0u8; 6];
+    reader.read_exact(&mut magic)?;
+    if magic == MAGIC_NUMBER {
+        Ok(Magic::Newc)
+    } else if magic == CRC_MAGIC_NUMBER {
+        Ok(Magic::Crc)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid magic number",
+        ))
+    }
+}
+
+/// Reads the header fields following a `magic` number returned by
+/// [`read_magic`]. The check value is only kept for `crc` entries.
+///
+/// Returns the entry metadata, with an empty name, along with the size of the
+/// name (including its NUL byte) that follows the header.
+pub fn read_header<R: Read>(reader: &mut R, magic: Magic) -> io::Result<(Entry, usize)> {
+    let mut raw = [0u8; HEADER_LEN];
+    raw[..MAGIC_NUMBER.len()].copy_from_slice(magic.bytes());
+    reader.read_exact(&mut raw[MAGIC_NUMBER.len()..])?;
+
+    let field = |field: Field| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&raw[field.range()]);
+        decode_hex8(&bytes)
+    };
+    let name_len = field(Field::NameSize)? as usize;
+    let check = field(Field::Check)?;
+    let entry = Entry {
+        name: String::new(),
+        ino: field(Field::Ino)?,
+        mode: field(Field::Mode)?,
+        uid: field(Field::Uid)?,
+        gid: field(Field::Gid)?,
+        nlink: field(Field::Nlink)?,
+        mtime: field(Field::Mtime)?,
+        file_size: field(Field::FileSize)?,
+        dev_major: field(Field::DevMajor)?,
+        dev_minor: field(Field::DevMinor)?,
+        rdev_major: field(Field::RdevMajor)?,
+        rdev_minor: field(Field::RdevMinor)?,
+        check: if magic == Magic::Crc {
+            Some(check)
+        } else {
+            None
+        },
+        raw: Some(raw),
+    };
+    Ok((entry, name_len))
+}
+
+/// Reads a NUL-terminated name of `name_len` bytes (including the NUL byte).
+pub fn read_name<R: Read>(reader: &mut R, name_len: usize) -> io::Result<String> {
+    if name_len < 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Entry name size is too small to hold a NUL byte",
+        ));
+    }
+    // Don't trust `name_len` for the allocation; only grow as data arrives.
+    let mut name_bytes = vec![];
+    reader.take(name_len as u64).read_to_end(&mut name_bytes)?;
+    if name_bytes.len() != name_len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Archive ended in the middle of an entry name",
+        ));
+    }
+    if name_bytes.last() != Some(&0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Entry name was not NUL-terminated",
+        ));
+    }
+    name_bytes.pop();
+    String::from_utf8(name_bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Entry name was not valid UTF-8"))
+}
+
+/// Skips the padding that follows `consumed` bytes to reach a multiple of
+/// `align` bytes. An `align` of 0 or 1 means there is no padding.
+pub fn skip_padding<R: Read>(reader: &mut R, align: usize, consumed: usize) -> io::Result<()> {
+    if align <= 1 {
+        return Ok(());
+    }
+    let overhang = consumed % align;
+    if overhang != 0 {
+        skip(reader, (align - overhang) as u64)?;
+    }
+    Ok(())
+}
+
+/// Skips `file_size` bytes of entry data along with the padding that follows
+/// it to reach a multiple of `align` bytes.
+pub fn skip_data<R: Read>(reader: &mut R, file_size: u64, align: usize) -> io::Result<()> {
+    skip(reader, file_size)?;
+    if align <= 1 {
+        return Ok(());
+    }
+    let overhang = file_size % align as u64;
+    if overhang != 0 {
+        skip(reader, align as u64 - overhang)?;
+    }
+    Ok(())
+}
+
+/// Discards `len` bytes, failing if the reader runs out first.
+///
+/// The bytes are read in bulk through `io::copy`'s buffer rather than one at a
+/// time, whether it's a few bytes of padding or the rest of a large file.
+fn skip<R: Read>(reader: &mut R, len: u64) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+    if skipped != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Archive ended in the middle of an entry",
+        ));
+    }
+    Ok(())
 }
 
 impl Entry {
+    /// Creates an entry with the given name and mode, which includes the file
+    /// type bits.
+    ///
+    /// Everything else is zeroed, except for the link count, which is 2 for a
+    /// directory and 1 otherwise.
+    pub fn new(name: &str, mode: u32) -> Entry {
+        Entry {
+            name: name.to_string(),
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlink: if mode & S_IFMT == S_IFDIR { 2 } else { 1 },
+            mtime: 0,
+            file_size: 0,
+            dev_major: 0,
+            dev_minor: 0,
+            rdev_major: 0,
+            rdev_minor: 0,
+            check: None,
+            raw: None,
+        }
+    }
+
+    /// Creates a FIFO (named pipe) entry with the given permission bits.
+    pub fn fifo(name: &str, mode: u32) -> Entry {
+        Entry::new(name, S_IFIFO | (mode & !S_IFMT))
+    }
+
     /// Returns the name of the file.
     pub fn name(&self) -> &str {
         &self.name
@@ -135,85 +544,124 @@ impl Entry {
         self.rdev_minor
     }
 
+    /// Returns the checksum of the file's data stored in a `crc` format
+    /// header, or `None` for other entries.
+    pub fn check(&self) -> Option<u32> {
+        self.check
+    }
+
+    /// Returns the bytes of a header field exactly as they were read from
+    /// the archive, or `None` if this entry wasn't read from one.
+    ///
+    /// These are not updated when the entry is modified.
+    pub fn raw_field(&self, field: Field) -> Option<&[u8]> {
+        self.raw.as_ref().map(|raw| &raw[field.range()])
+    }
+
+    /// Returns the whole header exactly as it was read from the archive,
+    /// magic number included, or `None` if this entry wasn't read from one.
+    ///
+    /// Like [`Entry::raw_field`], this isn't updated when the entry is
+    /// modified.
+    pub fn raw_header(&self) -> Option<&[u8]> {
+        self.raw.as_ref().map(|raw| &raw[..])
+    }
+
     /// Returns true if this is a trailer entry.
+    ///
+    /// Like GNU cpio, only the name is checked: an entry named `TRAILER!!!`
+    /// marks the end of the archive even if it has a non-zero file size.
     pub fn is_trailer(&self) -> bool {
         self.name == TRAILER_NAME
     }
+
+    /// Sets the name of the file.
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// Sets the permission bits of the file.
+    pub fn set_mode(&mut self, mode: u32) {
+        self.mode = mode;
+    }
+
+    /// Sets the UID for this file's owner.
+    pub fn set_uid(&mut self, uid: u32) {
+        self.uid = uid;
+    }
+
+    /// Sets the GID for this file's group.
+    pub fn set_gid(&mut self, gid: u32) {
+        self.gid = gid;
+    }
+
+    /// Sets the modification time of this file.
+    pub fn set_mtime(&mut self, mtime: u32) {
+        self.mtime = mtime;
+    }
+
+    /// Checks this entry's metadata for consistency before it is written,
+    /// returning a description of every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = vec![];
+
+        if self.name.is_empty() {
+            problems.push("name is empty".to_string());
+        }
+        if self.name.contains('\0') {
+            problems.push("name contains a NUL byte".to_string());
+        }
+
+        let file_type = self.mode & S_IFMT;
+        match file_type {
+            S_IFSOCK | S_IFLNK | S_IFREG | S_IFBLK | S_IFDIR | S_IFCHR | S_IFIFO => {}
+            _ => problems.push(format!("mode {:o} has no valid file type", self.mode)),
+        }
+        if file_type != S_IFBLK
+            && file_type != S_IFCHR
+            && (self.rdev_major != 0 || self.rdev_minor != 0)
+        {
+            problems.push("rdev is set on a non-device entry".to_string());
+        }
+        if file_type == S_IFDIR && self.file_size != 0 {
+            problems.push("directory has a non-zero file size".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Returns a builder carrying this entry's metadata, ready to be written
+    /// into another archive.
+    pub fn to_builder(&self) -> Builder {
+        Builder::new(&self.name)
+            .ino(self.ino)
+            .mode(self.mode)
+            .uid(self.uid)
+            .gid(self.gid)
+            .nlink(self.nlink)
+            .mtime(self.mtime)
+            .dev_major(self.dev_major)
+            .dev_minor(self.dev_minor)
+            .rdev_major(self.rdev_major)
+            .rdev_minor(self.rdev_minor)
+    }
 }
 
 impl<R: Read> Reader<R> {
     /// Parses metadata for the next entry in an archive, and returns a reader
     /// that will yield the entry data.
     pub fn new(mut inner: R) -> io::Result<Reader<R>> {
-        // char    c_magic[6];
-        let mut magic = [0u8; 6];
-        inner.read_exact(&mut magic)?;
-        if magic != MAGIC_NUMBER {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid magic number",
-            ));
-        }
-        // char    c_ino[8];
-        let ino = read_hex_u32(&mut inner)?;
-        // char    c_mode[8];
-        let mode = read_hex_u32(&mut inner)?;
-        // char    c_uid[8];
-        let uid = read_hex_u32(&mut inner)?;
-        // char    c_gid[8];
-        let gid = read_hex_u32(&mut inner)?;
-        // char    c_nlink[8];
-        let nlink = read_hex_u32(&mut inner)?;
-        // char    c_mtime[8];
-        let mtime = read_hex_u32(&mut inner)?;
-        // char    c_filesize[8];
-        let file_size = read_hex_u32(&mut inner)?;
-        // char    c_devmajor[8];
-        let dev_major = read_hex_u32(&mut inner)?;
-        // char    c_devminor[8];
-        let dev_minor = read_hex_u32(&mut inner)?;
-        // char    c_rdevmajor[8];
-        let rdev_major = read_hex_u32(&mut inner)?;
-        // char    c_rdevminor[8];
-        let rdev_minor = read_hex_u32(&mut inner)?;
-        // char    c_namesize[8];
-        let name_len = read_hex_u32(&mut inner)? as usize;
-        // char    c_checksum[8];
-        let _checksum = read_hex_u32(&mut inner)?;
-
-        // NUL-terminated name with length `name_len` (including NUL byte).
-        let mut name_bytes = vec![0u8; name_len];
-        inner.read_exact(&mut name_bytes)?;
-        if name_bytes.last() != Some(&0) {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Entry name was not NUL-terminated",
-            ));
-        }
-        name_bytes.pop();
-        let name = String::from_utf8(name_bytes).map_err(|_| {
-            io::Error::new(io::ErrorKind::InvalidData, "Entry name was not valid UTF-8")
-        })?;
+        let magic = read_magic(&mut inner)?;
+        let (mut entry, name_len) = read_header(&mut inner, magic)?;
+        entry.name = read_name(&mut inner, name_len)?;
 
         // Pad out to a multiple of 4 bytes.
-        if let Some(mut padding) = pad(HEADER_LEN + name_len) {
-            inner.read_exact(&mut padding)?;
-        }
+        skip_padding(&mut inner, 4, HEADER_LEN + name_len)?;
 
-        let entry = Entry {
-            name,
-            ino,
-            mode,
-            uid,
-            gid,
-            nlink,
-            mtime,
-            file_size,
-            dev_major,
-            dev_minor,
-            rdev_major,
-            rdev_minor,
-        };
         Ok(Reader {
             inner,
             entry,
@@ -230,15 +678,8 @@ impl<R: Read> Reader<R> {
     /// position ready to read the next entry (if any).
     pub fn finish(mut self) -> io::Result<R> {
         let remaining = self.entry.file_size - self.bytes_read;
-        if remaining > 0 {
-            io::copy(
-                &mut self.inner.by_ref().take(remaining as u64),
-                &mut io::sink(),
-            )?;
-        }
-        if let Some(mut padding) = pad(self.entry.file_size as usize) {
-            self.inner.read_exact(&mut padding)?;
-        }
+        skip(&mut self.inner, u64::from(remaining))?;
+        skip_padding(&mut self.inner, 4, self.entry.file_size as usize)?;
         Ok(self.inner)
     }
 }
@@ -271,6 +712,7 @@ impl Builder {
             dev_minor: 0,
             rdev_major: 0,
             rdev_minor: 0,
+            padding_byte: 0,
         }
     }
 
@@ -324,15 +766,29 @@ impl Builder {
         self
     }
 
+    /// Sets the byte used to pad the header and data out to a multiple of 4
+    /// bytes. Defaults to NUL.
+    ///
+    /// Anything other than NUL is non-standard; this only exists to check that
+    /// readers ignore the content of padding.
+    pub fn padding_byte(mut self, padding_byte: u8) -> Builder {
+        self.padding_byte = padding_byte;
+        self
+    }
+
     pub fn write<W: Write>(self, w: W, file_size: u32) -> Writer<W> {
+        let name = self.name.clone();
+        let padding_byte = self.padding_byte;
         let header = self.into_header(file_size);
 
         Writer {
             inner: w,
+            name,
+            padding_byte,
             written: 0,
-            file_size: file_size,
+            file_size,
             header_size: header.len(),
-            header: header,
+            header,
         }
     }
 
@@ -374,7 +830,7 @@ impl Builder {
         header.push(0u8);
 
         // pad out to a multiple of 4 bytes
-        if let Some(pad) = pad(HEADER_LEN + name_len) {
+        if let Some(pad) = pad(HEADER_LEN + name_len, self.padding_byte) {
             header.extend(pad);
         }
 
@@ -389,8 +845,17 @@ impl<W: Write> Writer<W> {
     }
 
     fn try_write_header(&mut self) -> io::Result<()> {
-        if self.header.len() != 0 {
-            self.inner.write_all(&self.header)?;
+        if !self.header.is_empty() {
+            let name_end = HEADER_LEN + self.name.len() + 1;
+            self.inner
+                .write_all(&self.header[..HEADER_LEN])
+                .map_err(|err| self.context(err, "header"))?;
+            self.inner
+                .write_all(&self.header[HEADER_LEN..name_end])
+                .map_err(|err| self.context(err, "name"))?;
+            self.inner
+                .write_all(&self.header[name_end..])
+                .map_err(|err| self.context(err, "padding"))?;
             self.header.truncate(0);
         }
         Ok(())
@@ -400,14 +865,34 @@ impl<W: Write> Writer<W> {
         self.try_write_header()?;
 
         if self.written == self.file_size {
-            if let Some(pad) = pad(self.header_size + self.file_size as usize) {
-                self.inner.write(&pad)?;
-                self.inner.flush()?;
+            if let Some(pad) = pad(
+                self.header_size + self.file_size as usize,
+                self.padding_byte,
+            ) {
+                self.inner
+                    .write_all(&pad)
+                    .map_err(|err| self.context(err, "padding"))?;
+                self.inner
+                    .flush()
+                    .map_err(|err| self.context(err, "flush"))?;
             }
         }
 
         Ok(())
     }
+
+    /// Wraps an error from the underlying writer with the entry name and the
+    /// part of the entry that was being written.
+    fn context(&self, err: io::Error, phase: &'static str) -> io::Error {
+        io::Error::new(
+            err.kind(),
+            WriteError {
+                name: self.name.clone(),
+                phase,
+                source: err,
+            },
+        )
+    }
 }
 
 impl<W: Write> Write for Writer<W> {
@@ -415,7 +900,10 @@ impl<W: Write> Write for Writer<W> {
         if self.written + buf.len() as u32 <= self.file_size {
             self.try_write_header()?;
 
-            let n = self.inner.write(buf)?;
+            let n = self
+                .inner
+                .write(buf)
+                .map_err(|err| self.context(err, "data"))?;
             self.written += n as u32;
             Ok(n)
         } else {
@@ -432,12 +920,239 @@ impl<W: Write> Write for Writer<W> {
 }
 
 /// Writes a trailer entry into an archive.
+///
+/// Only the padding required to align the trailer entry itself is written, so
+/// the archive ends immediately after it with no extra block padding.
 pub fn trailer<W: Write>(w: W) -> io::Result<W> {
     let b = Builder::new(TRAILER_NAME).nlink(0);
     let writer = b.write(w, 0);
     writer.finish()
 }
 
+/// Writes an entry into an archive exactly as given, without deriving any of
+/// its header fields.
+///
+/// `header` and `name` are written back to back and followed by padding to a
+/// multiple of 4 bytes, then `data` and its own padding. Nothing checks that
+/// the header actually describes the name and data, which makes this useful
+/// for crafting unusual archives to test other parsers with.
+///
+/// The header must still start with the `newc` magic number, so that a raw
+/// entry can't switch the archive to a different format part way through.
+pub fn raw_entry<W: Write>(mut w: W, header: &[u8], name: &[u8], data: &[u8]) -> io::Result<W> {
+    if !header.starts_with(MAGIC_NUMBER) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Raw entry header does not start with the newc magic number",
+        ));
+    }
+    w.write_all(header)?;
+    w.write_all(name)?;
+    if let Some(pad) = pad(header.len() + name.len(), 0) {
+        w.write_all(&pad)?;
+    }
+    w.write_all(data)?;
+    if let Some(pad) = pad(data.len(), 0) {
+        w.write_all(&pad)?;
+    }
+    Ok(w)
+}
+
+/// Checks that an archive is usable as a Linux initramfs, returning warnings
+/// about anything likely to cause boot problems.
+///
+/// Like the kernel, this reads every archive in `r` rather than stopping at
+/// the first trailer, so an early microcode archive may come before the main
+/// one. NUL padding between archives is skipped. Both the `newc` and `crc`
+/// formats are accepted, though the checksums of a `crc` archive aren't
+/// verified. An archive in any other format, or that can't be read, is an
+/// error rather than a warning.
+pub fn validate_initramfs<R: Read>(r: R) -> io::Result<Vec<String>> {
+    let mut warnings = vec![];
+    let mut init = None;
+    let mut console = None;
+
+    let mut r = io::BufReader::new(r);
+    loop {
+        let reader = Reader::new(&mut r)?;
+        if reader.entry().is_trailer() {
+            reader.finish()?;
+            if skip_nul_padding(&mut r)? {
+                continue;
+            }
+            break;
+        }
+
+        let name = reader.entry().name();
+        if name.starts_with('/') {
+            warnings.push(format!("`{}` is an absolute path", name));
+        }
+        // The kernel unpacks everything relative to the root of rootfs.
+        let path = name.trim_start_matches("./").trim_start_matches('/');
+        match path {
+            "init" => init = Some(reader.entry().mode()),
+            "dev/console" => console = Some(reader.entry().mode()),
+            _ => {}
+        }
+
+        reader.finish()?;
+    }
+
+    match init {
+        None => warnings.push("no /init, so the kernel will look for a root device".to_string()),
+        Some(mode) => {
+            let file_type = mode & S_IFMT;
+            if file_type != S_IFREG && file_type != S_IFLNK {
+                warnings.push("/init is not a regular file or symlink".to_string());
+            } else if file_type == S_IFREG && mode & 0o111 == 0 {
+                warnings.push("/init is not executable".to_string());
+            }
+        }
+    }
+    match console {
+        None => warnings.push("no /dev/console, so /init will start without one".to_string()),
+        Some(mode) if mode & S_IFMT != S_IFCHR => {
+            warnings.push("/dev/console is not a character device".to_string())
+        }
+        Some(_) => {}
+    }
+
+    Ok(warnings)
+}
+
+/// Skips NUL bytes, returning whether anything follows them.
+fn skip_nul_padding<R: BufRead>(r: &mut R) -> io::Result<bool> {
+    loop {
+        let (nuls, more) = {
+            let buf = r.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(false);
+            }
+            let nuls = buf.iter().take_while(|&&byte| byte == 0).count();
+            (nuls, nuls < buf.len())
+        };
+        r.consume(nuls);
+        if more {
+            return Ok(true);
+        }
+    }
+}
+
+/// Checks the data of every entry in a `crc` format archive against the
+/// checksum in its header, like GNU cpio's `--only-verify-crc`.
+///
+/// Returns the name of each entry along with whether its checksum matched.
+/// Fails if the archive isn't in the `crc` format.
+pub fn verify_crc_archive<R: Read>(mut r: R) -> io::Result<Vec<(String, bool)>> {
+    let mut results = vec![];
+    loop {
+        let mut reader = Reader::new(r)?;
+        let expected = match reader.entry().check() {
+            Some(check) => check,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Archive is not in the crc format",
+                ))
+            }
+        };
+        if reader.entry().is_trailer() {
+            break;
+        }
+
+        // The check is the sum of all data bytes, truncated to 32 bits.
+        let mut sum = 0u32;
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            sum = buf[..n]
+                .iter()
+                .fold(sum, |sum, &byte| sum.wrapping_add(u32::from(byte)));
+        }
+        // Fails on a truncated entry, before a bogus result is recorded.
+        let name = reader.entry().name().to_string();
+        r = reader.finish()?;
+        results.push((name, sum == expected));
+    }
+    Ok(results)
+}
+
+/// Repairs an archive whose `namesize` or `filesize` header fields don't
+/// match the actual name and data lengths, returning a corrected copy.
+///
+/// This is best-effort. The name is taken to end at its first NUL byte. When
+/// the next header isn't where the entry's file size says it should be, the
+/// data is taken to run up to the next well-formed header, less up to 3
+/// bytes of NUL padding; data that happens to end in NUL bytes, or to
+/// contain something that looks like a header, will defeat it. Everything up
+/// to and including the trailer is kept, and anything after it is dropped.
+pub fn repair(archive: &[u8]) -> io::Result<Vec<u8>> {
+    fn header_at(archive: &[u8], pos: usize) -> Option<Entry> {
+        let mut input = archive.get(pos..)?;
+        let magic = read_magic(&mut input).ok()?;
+        read_header(&mut input, magic).ok().map(|(entry, _)| entry)
+    }
+
+    fn align(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    let corrupt = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut output = vec![];
+    let mut pos = 0;
+    loop {
+        let entry = header_at(archive, pos).ok_or_else(|| corrupt("Invalid entry header"))?;
+
+        let name_start = pos + HEADER_LEN;
+        let name_len = archive[name_start..]
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| corrupt("Entry name was not NUL-terminated"))?
+            + 1;
+        let name = &archive[name_start..name_start + name_len];
+        let is_trailer = &name[..name_len - 1] == TRAILER_NAME.as_bytes();
+
+        let data_start = align(name_start + name_len).min(archive.len());
+        let mut file_size = entry.file_size() as usize;
+        let expected_next = align(data_start + file_size);
+        if is_trailer {
+            file_size = file_size.min(archive.len() - data_start);
+        } else if header_at(archive, expected_next).is_none() {
+            // Find the next header, which is always 4-byte aligned.
+            let next = (data_start..archive.len())
+                .step_by(4)
+                .find(|&next| header_at(archive, next).is_some())
+                .ok_or_else(|| corrupt("Could not find the header after a corrupt entry"))?;
+            let padding = archive[data_start..next]
+                .iter()
+                .rev()
+                .take(3)
+                .take_while(|&&byte| byte == 0)
+                .count();
+            file_size = next - data_start - padding;
+        }
+
+        let mut header = archive[pos..name_start].to_vec();
+        header[Field::FileSize.range()].copy_from_slice(format!("{:08x}", file_size).as_bytes());
+        header[Field::NameSize.range()].copy_from_slice(format!("{:08x}", name_len).as_bytes());
+        let data = &archive[data_start..data_start + file_size];
+        output.extend(&header);
+        output.extend(name);
+        output.resize(align(output.len()), 0);
+        output.extend(data);
+        output.resize(align(output.len()), 0);
+
+        if is_trailer {
+            return Ok(output);
+        }
+        pos = align(data_start + file_size);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -542,4 +1257,839 @@ mod tests {
         let reader = Reader::new(reader.finish().unwrap()).unwrap();
         assert!(reader.entry().is_trailer());
     }
+
+    /// Builds a `crc` format archive by patching the magic and check fields of
+    /// entries written by `Builder`.
+    fn crc_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut output = vec![];
+        for &(name, data) in files {
+            let start = output.len();
+            let mut writer = Builder::new(name)
+                .mode(0o100644)
+                .write(output, data.len() as u32);
+            writer.write_all(data).unwrap();
+            output = writer.finish().unwrap();
+
+            let check = data
+                .iter()
+                .fold(0u32, |sum, &byte| sum.wrapping_add(u32::from(byte)));
+            output[start..start + 6].copy_from_slice(CRC_MAGIC_NUMBER);
+            output[start + 102..start + HEADER_LEN]
+                .copy_from_slice(format!("{:08x}", check).as_bytes());
+        }
+        let start = output.len();
+        let mut output = trailer(output).unwrap();
+        output[start..start + 6].copy_from_slice(CRC_MAGIC_NUMBER);
+        output
+    }
+
+    #[test]
+    fn test_crc_check() {
+        let archive = crc_archive(&[("./a", b"Hello")]);
+        assert_eq!(read_magic(&mut archive.as_slice()).unwrap(), Magic::Crc);
+        let mut reader = Reader::new(archive.as_slice()).unwrap();
+        assert_eq!(reader.entry().name(), "./a");
+        assert_eq!(reader.entry().check(), Some(500));
+        let mut contents = vec![];
+        copy(&mut reader, &mut contents).unwrap();
+        assert_eq!(contents, b"Hello");
+        let reader = Reader::new(reader.finish().unwrap()).unwrap();
+        assert!(reader.entry().is_trailer());
+
+        let output = Builder::new("./a").write(vec![], 0).finish().unwrap();
+        let reader = Reader::new(output.as_slice()).unwrap();
+        assert_eq!(reader.entry().check(), None);
+    }
+
+    #[test]
+    fn test_verify_crc_archive() {
+        let mut archive = crc_archive(&[("./a", b"Hello"), ("./b", b"World"), ("./c", b"")]);
+        // Flip a byte of the second file's data.
+        let offset = 2 * HEADER_LEN + 6 + 8 + 6;
+        assert_eq!(archive[offset], b'W');
+        archive[offset] = b'w';
+
+        let results = verify_crc_archive(archive.as_slice()).unwrap();
+        assert_eq!(
+            results,
+            [
+                ("./a".to_string(), true),
+                ("./b".to_string(), false),
+                ("./c".to_string(), true),
+            ]
+        );
+
+        let output = trailer(Builder::new("./a").write(vec![], 0).finish().unwrap()).unwrap();
+        let err = verify_crc_archive(output.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Entry::new("./file", 0o100644).validate().is_ok());
+
+        let mut device = Entry::new("./dev/null", 0o020666);
+        device.rdev_major = 1;
+        device.rdev_minor = 3;
+        assert!(device.validate().is_ok());
+
+        assert_eq!(Entry::new("", 0o100644).validate().unwrap_err().len(), 1);
+        assert_eq!(
+            Entry::new("./a\0b", 0o100644).validate().unwrap_err().len(),
+            1
+        );
+        assert_eq!(Entry::new("./file", 0o644).validate().unwrap_err().len(), 1);
+
+        let mut file = Entry::new("./file", 0o100644);
+        file.rdev_major = 1;
+        assert_eq!(file.validate().unwrap_err().len(), 1);
+
+        let mut dir = Entry::new("./dir", 0o040755);
+        dir.file_size = 4096;
+        assert_eq!(dir.validate().unwrap_err().len(), 1);
+
+        assert_eq!(Entry::new("", 0).validate().unwrap_err().len(), 2);
+    }
+
+    /// Accepts a fixed number of bytes, then fails every write.
+    /// Also fails every flush if `fail_flush` is set.
+    struct FailingWriter {
+        remaining: usize,
+        fail_flush: bool,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+            }
+            let n = buf.len().min(self.remaining);
+            self.remaining -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if self.fail_flush {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_error_context() {
+        let data: &[u8] = b"Hello";
+
+        // "./a" and its NUL leave 2 bytes of header padding, and the 5 data
+        // bytes are followed by 3 more.
+        let cases = [
+            (0, "header"),
+            (HEADER_LEN, "name"),
+            (HEADER_LEN + 4, "padding"),
+            (HEADER_LEN + 6, "data"),
+            (HEADER_LEN + 6 + data.len(), "padding"),
+        ];
+        let mut cases: Vec<_> = cases
+            .iter()
+            .map(|&(remaining, phase)| {
+                let output = FailingWriter {
+                    remaining,
+                    fail_flush: false,
+                };
+                (output, phase)
+            })
+            .collect();
+        // Flushing after the final padding fails.
+        let flush = FailingWriter {
+            remaining: usize::MAX,
+            fail_flush: true,
+        };
+        cases.push((flush, "flush"));
+
+        for (output, phase) in cases {
+            let mut writer = Builder::new("./a").write(output, data.len() as u32);
+            let err = writer
+                .write_all(data)
+                .and_then(|_| writer.finish().map(|_| ()))
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+            let message = match phase {
+                "flush" => "failed to flush `./a`: disk full".to_string(),
+                _ => format!("failed to write {} of `./a`: disk full", phase),
+            };
+            assert_eq!(err.to_string(), message);
+
+            // The original error is still reachable.
+            let context = err.get_ref().unwrap().downcast_ref::<WriteError>().unwrap();
+            assert_eq!(context.name(), "./a");
+            assert_eq!(context.phase(), phase);
+            let source = error::Error::source(context).unwrap();
+            assert_eq!(source.to_string(), "disk full");
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let entry = Entry::new("./file", 0o100644);
+        assert_eq!(entry.name(), "./file");
+        assert_eq!(entry.mode(), 0o100644);
+        assert_eq!(entry.ino(), 0);
+        assert_eq!(entry.uid(), 0);
+        assert_eq!(entry.gid(), 0);
+        assert_eq!(entry.nlink(), 1);
+        assert_eq!(entry.mtime(), 0);
+        assert_eq!(entry.file_size(), 0);
+        assert_eq!(entry.rdev_major(), 0);
+        assert_eq!(entry.rdev_minor(), 0);
+        assert_eq!(entry.check(), None);
+        assert!(entry.validate().is_ok());
+
+        assert_eq!(Entry::new("./dir", 0o040755).nlink(), 2);
+    }
+
+    #[test]
+    fn test_fifo() {
+        let fifo = Entry::fifo("./pipe", 0o640);
+        assert!(fifo.validate().is_ok());
+
+        let output = fifo.to_builder().write(vec![], 0).finish().unwrap();
+        let output = trailer(output).unwrap();
+
+        let reader = Reader::new(output.as_slice()).unwrap();
+        assert_eq!(reader.entry().name(), "./pipe");
+        assert_eq!(reader.entry().mode(), 0o010640);
+        assert_eq!(reader.entry().file_size(), 0);
+        let reader = Reader::new(reader.finish().unwrap()).unwrap();
+        assert!(reader.entry().is_trailer());
+    }
+
+    #[test]
+    fn test_manual_parse() {
+        let data: &[u8] = b"Hello, World";
+        let mut writer = Builder::new("./hello_world")
+            .mode(0o100644)
+            .write(vec![], data.len() as u32);
+        writer.write_all(data).unwrap();
+        let output = trailer(writer.finish().unwrap()).unwrap();
+
+        let mut input = output.as_slice();
+        let magic = read_magic(&mut input).unwrap();
+        assert_eq!(magic, Magic::Newc);
+        let (entry, name_len) = read_header(&mut input, magic).unwrap();
+        assert_eq!(entry.mode(), 0o100644);
+        assert_eq!(entry.file_size(), data.len() as u32);
+        assert_eq!(read_name(&mut input, name_len).unwrap(), "./hello_world");
+        skip_padding(&mut input, 4, HEADER_LEN + name_len).unwrap();
+        skip_data(&mut input, entry.file_size() as u64, 4).unwrap();
+
+        // The next entry is the trailer.
+        let magic = read_magic(&mut input).unwrap();
+        let (_, name_len) = read_header(&mut input, magic).unwrap();
+        assert_eq!(read_name(&mut input, name_len).unwrap(), TRAILER_NAME);
+    }
+
+    /// A small xorshift generator, so the fuzz tests are reproducible.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    /// Reads every entry of `archive`, stopping at the first error.
+    fn read_all(archive: &[u8]) {
+        let mut input = archive;
+        for _ in 0..16 {
+            let mut reader = match Reader::new(input) {
+                Ok(reader) => reader,
+                Err(_) => return,
+            };
+            if reader.entry().is_trailer() {
+                return;
+            }
+            if copy(&mut reader, &mut io::sink()).is_err() {
+                return;
+            }
+            input = match reader.finish() {
+                Ok(input) => input,
+                Err(_) => return,
+            };
+        }
+    }
+
+    #[test]
+    fn test_fuzz_random_bytes() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..1000 {
+            let len = (rng.next() % 512) as usize;
+            let mut archive: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            // Give half of the inputs a valid magic so parsing gets further.
+            if rng.next() & 1 == 0 && archive.len() >= MAGIC_NUMBER.len() {
+                archive[..MAGIC_NUMBER.len()].copy_from_slice(MAGIC_NUMBER);
+            }
+            read_all(&archive);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_mutated_archive() {
+        let data: &[u8] = b"Hello, World";
+        let mut writer = Builder::new("./hello_world").write(vec![], data.len() as u32);
+        writer.write_all(data).unwrap();
+        let archive = trailer(writer.finish().unwrap()).unwrap();
+
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..1000 {
+            let mut mutated = archive.clone();
+            for _ in 0..(rng.next() % 4 + 1) {
+                let idx = (rng.next() as usize) % mutated.len();
+                // Favour hex digits and 'f' so header fields stay parseable.
+                mutated[idx] = match rng.next() % 3 {
+                    0 => b'f',
+                    1 => b"0123456789abcdef"[(rng.next() % 16) as usize],
+                    _ => rng.next() as u8,
+                };
+            }
+            let truncate = (rng.next() as usize) % (mutated.len() + 1);
+            read_all(&mutated);
+            read_all(&mutated[..truncate]);
+        }
+    }
+
+    /// Builds a `newc` header for an empty regular file, with the given
+    /// fields replaced by their (unchecked) hex digits.
+    fn header(overrides: &[(Field, &str)]) -> String {
+        let mut header = b"070701".to_vec();
+        header.resize(HEADER_LEN, b'0');
+        header[Field::Mode.range()].copy_from_slice(b"000081a4");
+        header[Field::Nlink.range()].copy_from_slice(b"00000001");
+        for &(field, digits) in overrides {
+            header[field.range()].copy_from_slice(digits.as_bytes());
+        }
+        String::from_utf8(header).unwrap()
+    }
+
+    #[test]
+    fn test_raw_entry() {
+        // Upper-case hex and a non-zero check field, neither of which the
+        // builder would produce.
+        let header = header(&[
+            (Field::Ino, "0000002A"),
+            (Field::Mode, "000081A4"),
+            (Field::Uid, "000003E8"),
+            (Field::Gid, "000003E8"),
+            (Field::Mtime, "DEADBEEF"),
+            (Field::FileSize, "00000005"),
+            (Field::NameSize, "00000006"),
+            (Field::Check, "ABCDEF01"),
+        ]);
+        assert_eq!(header.len(), HEADER_LEN);
+
+        let output = raw_entry(vec![], header.as_bytes(), b"./raw\0", b"Hello").unwrap();
+        let output = trailer(output).unwrap();
+
+        let mut reader = Reader::new(output.as_slice()).unwrap();
+        assert_eq!(reader.entry().name(), "./raw");
+        assert_eq!(reader.entry().ino(), 42);
+        assert_eq!(reader.entry().mode(), 0o100644);
+        assert_eq!(reader.entry().mtime(), 0xdeadbeef);
+        let mut contents = vec![];
+        copy(&mut reader, &mut contents).unwrap();
+        assert_eq!(contents, b"Hello");
+        let reader = Reader::new(reader.finish().unwrap()).unwrap();
+        assert!(reader.entry().is_trailer());
+    }
+
+    #[test]
+    fn test_raw_entry_wrong_magic() {
+        // A 76-byte odc header, which starts with the magic 070707.
+        let header = "0707070000000000011006440000000000000000010000000000000000000000200000000001";
+        let err = raw_entry(vec![], header.as_bytes(), b"a\0", b"x")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_trailer_with_data() {
+        let mut writer = Builder::new(TRAILER_NAME).write(vec![], 4);
+        writer.write_all(b"data").unwrap();
+        let output = writer.finish().unwrap();
+        let writer = Builder::new("./after").write(output, 0);
+        let output = trailer(writer.finish().unwrap()).unwrap();
+
+        // GNU cpio stops at the first entry with the trailer name, whatever
+        // its size.
+        let mut reader = Reader::new(output.as_slice()).unwrap();
+        assert!(reader.entry().is_trailer());
+        assert_eq!(reader.entry().file_size(), 4);
+        let mut contents = vec![];
+        copy(&mut reader, &mut contents).unwrap();
+        assert_eq!(contents, b"data");
+    }
+
+    #[test]
+    fn test_empty_name() {
+        let header = header(&[(Field::FileSize, "00000001"), (Field::NameSize, "00000001")]);
+        let output = raw_entry(vec![], header.as_bytes(), b"\0", b"x").unwrap();
+        let output = trailer(output).unwrap();
+
+        let mut reader = Reader::new(output.as_slice()).unwrap();
+        assert_eq!(reader.entry().name(), "");
+        let mut contents = vec![];
+        copy(&mut reader, &mut contents).unwrap();
+        assert_eq!(contents, b"x");
+        let reader = Reader::new(reader.finish().unwrap()).unwrap();
+        assert!(reader.entry().is_trailer());
+    }
+
+    #[test]
+    fn test_zero_name_len() {
+        let header = header(&[(Field::FileSize, "00000001"), (Field::NameSize, "00000000")]);
+        let output = raw_entry(vec![], header.as_bytes(), b"", b"x").unwrap();
+        let output = trailer(output).unwrap();
+
+        let err = Reader::new(output.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_padding_byte() {
+        let data: &[u8] = b"Hello";
+        let mut writer = Builder::new("./a")
+            .padding_byte(0xff)
+            .write(vec![], data.len() as u32);
+        writer.write_all(data).unwrap();
+        let output = writer.finish().unwrap();
+
+        // 2 bytes after the name and 3 after the data.
+        assert_eq!(&output[HEADER_LEN..HEADER_LEN + 6], b"./a\0\xff\xff");
+        assert_eq!(&output[HEADER_LEN + 11..], b"\xff\xff\xff");
+
+        let output = trailer(output).unwrap();
+        let mut reader = Reader::new(output.as_slice()).unwrap();
+        assert_eq!(reader.entry().name(), "./a");
+        let mut contents = vec![];
+        copy(&mut reader, &mut contents).unwrap();
+        assert_eq!(contents, data);
+        let reader = Reader::new(reader.finish().unwrap()).unwrap();
+        assert!(reader.entry().is_trailer());
+    }
+
+    #[test]
+    fn test_decode_hex8() {
+        fn decode(field: &[u8]) -> io::Result<u32> {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(field);
+            decode_hex8(&bytes)
+        }
+
+        let mut rng = XorShift(0xdead_beef_cafe_f00d);
+        for _ in 0..10000 {
+            let value = rng.next() as u32;
+            assert_eq!(decode(format!("{:08x}", value).as_bytes()).unwrap(), value);
+            assert_eq!(decode(format!("{:08X}", value).as_bytes()).unwrap(), value);
+        }
+        assert_eq!(decode(b"00000000").unwrap(), 0);
+        assert_eq!(decode(b"ffffffff").unwrap(), u32::MAX);
+
+        assert_eq!(decode(b" 0000001").unwrap(), 1);
+        assert_eq!(decode(b"     1a4").unwrap(), 0x1a4);
+        assert_eq!(decode(b"       F").unwrap(), 0xf);
+
+        let invalid: [&[u8]; 9] = [
+            b"0000000g",
+            b"+0000001",
+            b"-0000001",
+            b"        ",
+            b"0000 001",
+            b"0000001 ",
+            b"\t0000001",
+            b"0000000\0",
+            b"\xff0000000",
+        ];
+        for field in invalid.iter() {
+            assert!(decode(field).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_magic() {
+        for &(name, magic) in [
+            ("newc", Magic::Newc),
+            ("sv4cpio", Magic::Newc),
+            ("crc", Magic::Crc),
+            ("sv4crc", Magic::Crc),
+            ("NEWC", Magic::Newc),
+        ]
+        .iter()
+        {
+            assert_eq!(name.parse::<Magic>(), Ok(magic));
+        }
+
+        // Formats this crate can't read are unknown too.
+        for name in ["odc", "bin", "tar", "", " newc"].iter() {
+            let err = name.parse::<Magic>().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("unsupported archive format `{}`", name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let mut output = vec![];
+        for name in ["./c", "./a", "./d/e", "./b", "./d"].iter() {
+            output = Builder::new(name).write(output, 0).finish().unwrap();
+        }
+        let output = trailer(output).unwrap();
+
+        let mut entries = vec![];
+        let mut input = output.as_slice();
+        loop {
+            let reader = Reader::new(input).unwrap();
+            if reader.entry().is_trailer() {
+                break;
+            }
+            entries.push(reader.entry().clone());
+            input = reader.finish().unwrap();
+        }
+
+        entries.sort();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["./a", "./b", "./c", "./d", "./d/e"]);
+
+        // Equal names fall back to the other fields.
+        let mut small = Entry::new("./a", 0o100644);
+        let mut large = small.clone();
+        small.ino = 1;
+        large.ino = 2;
+        assert!(small < large);
+    }
+
+    #[test]
+    fn test_skip_large_archive() {
+        // Sizes cover every padding length, from a handful of bytes up.
+        let mut rng = XorShift(0x0123_4567_89ab_cdef);
+        let sizes: Vec<usize> = (0..200)
+            .map(|i| match i % 4 {
+                0 => 100_000 + i,
+                _ => (rng.next() % 5000) as usize,
+            })
+            .collect();
+
+        let mut output = vec![];
+        for (i, &size) in sizes.iter().enumerate() {
+            let data = vec![i as u8; size];
+            let mut writer = Builder::new(&format!("./file{}", i)).write(output, size as u32);
+            writer.write_all(&data).unwrap();
+            output = writer.finish().unwrap();
+        }
+        let output = trailer(output).unwrap();
+
+        // Read part of some bodies and none of others, leaving the rest to
+        // be skipped by `finish`.
+        let mut input = output.as_slice();
+        for (i, &size) in sizes.iter().enumerate() {
+            let mut reader = Reader::new(input).unwrap();
+            assert_eq!(reader.entry().name(), format!("./file{}", i));
+            assert_eq!(reader.entry().file_size() as usize, size);
+            let mut contents = vec![];
+            copy(&mut reader.by_ref().take((i % 3 * 7) as u64), &mut contents).unwrap();
+            assert!(contents.iter().all(|&byte| byte == i as u8));
+            input = reader.finish().unwrap();
+        }
+        let reader = Reader::new(input).unwrap();
+        assert!(reader.entry().is_trailer());
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let mut writer = Builder::new("./a").write(vec![], 5);
+        writer.write_all(b"Hello").unwrap();
+        let output = writer.finish().unwrap();
+
+        let reader = Reader::new(&output[..output.len() - 4]).unwrap();
+        let err = reader.finish().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_validate_initramfs() {
+        let mut output = Builder::new(".")
+            .mode(0o040755)
+            .write(vec![], 0)
+            .finish()
+            .unwrap();
+        output = Builder::new("dev")
+            .mode(0o040755)
+            .write(output, 0)
+            .finish()
+            .unwrap();
+        output = Builder::new("dev/console")
+            .mode(0o020600)
+            .rdev_major(5)
+            .rdev_minor(1)
+            .write(output, 0)
+            .finish()
+            .unwrap();
+        let mut writer = Builder::new("init").mode(0o100755).write(output, 2);
+        writer.write_all(b"#!").unwrap();
+        let output = trailer(writer.finish().unwrap()).unwrap();
+        assert!(validate_initramfs(output.as_slice()).unwrap().is_empty());
+
+        // A non-executable `/init` under an absolute name.
+        let mut output = Builder::new("/dev/console")
+            .mode(0o020600)
+            .write(vec![], 0)
+            .finish()
+            .unwrap();
+        output = Builder::new("/init")
+            .mode(0o100644)
+            .write(output, 0)
+            .finish()
+            .unwrap();
+        let output = trailer(output).unwrap();
+        let warnings = validate_initramfs(output.as_slice()).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "`/dev/console` is an absolute path",
+                "`/init` is an absolute path",
+                "/init is not executable",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_initramfs_concatenated() {
+        // An early microcode archive, padded out to 512 bytes, followed by the
+        // main one.
+        let mut output = Builder::new("kernel/x86/microcode/GenuineIntel.bin")
+            .mode(0o100644)
+            .write(vec![], 0)
+            .finish()
+            .unwrap();
+        output = trailer(output).unwrap();
+        output.resize(512, 0);
+        output = Builder::new("dev/console")
+            .mode(0o020600)
+            .write(output, 0)
+            .finish()
+            .unwrap();
+        output = Builder::new("init")
+            .mode(0o100755)
+            .write(output, 0)
+            .finish()
+            .unwrap();
+        let mut output = trailer(output).unwrap();
+        assert!(validate_initramfs(output.as_slice()).unwrap().is_empty());
+
+        // Trailing NUL padding is fine, but anything else must be an archive.
+        output.extend_from_slice(&[0; 12]);
+        assert!(validate_initramfs(output.as_slice()).unwrap().is_empty());
+        output.extend_from_slice(b"not an archive");
+        let err = validate_initramfs(output.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_validate_initramfs_no_init() {
+        let output = Builder::new("./dev/console")
+            .mode(0o020600)
+            .write(vec![], 0)
+            .finish()
+            .unwrap();
+        let output = trailer(output).unwrap();
+        let warnings = validate_initramfs(output.as_slice()).unwrap();
+        assert_eq!(
+            warnings,
+            ["no /init, so the kernel will look for a root device"]
+        );
+
+        // The kernel also unpacks `crc` archives.
+        let output = crc_archive(&[("./dev/console", b"")]);
+        let warnings = validate_initramfs(output.as_slice()).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "no /init, so the kernel will look for a root device",
+                "/dev/console is not a character device",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mixed_case_hex() {
+        assert_eq!(decode_hex8(b"0A1b2C3d").unwrap(), 0x0a1b_2c3d);
+
+        let header = header(&[(Field::Mtime, "0A1b2C3d"), (Field::NameSize, "00000004")]);
+        let output = raw_entry(vec![], header.as_bytes(), b"./a\0", b"").unwrap();
+        let reader = Reader::new(output.as_slice()).unwrap();
+        assert_eq!(reader.entry().mtime(), 0x0a1b_2c3d);
+    }
+
+    /// Reads every entry of `archive`, returning each name and contents.
+    fn read_contents(archive: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let mut found = vec![];
+        let mut input = archive;
+        loop {
+            let mut reader = Reader::new(input)?;
+            if reader.entry().is_trailer() {
+                return Ok(found);
+            }
+            let mut contents = vec![];
+            reader.read_to_end(&mut contents)?;
+            found.push((reader.entry().name().to_string(), contents));
+            input = reader.finish()?;
+        }
+    }
+
+    #[test]
+    fn test_repair() {
+        let files: [(&str, &[u8]); 3] =
+            [("./a", b"Hello"), ("./b", b"Hello, World"), ("./c", b"!")];
+        let mut archive = vec![];
+        for &(name, data) in files.iter() {
+            let mut writer = Builder::new(name).write(archive, data.len() as u32);
+            writer.write_all(data).unwrap();
+            archive = writer.finish().unwrap();
+        }
+        let archive = trailer(archive).unwrap();
+        let expected: Vec<_> = files
+            .iter()
+            .map(|&(name, data)| (name.to_string(), data.to_vec()))
+            .collect();
+
+        // Repairing an intact archive leaves it as it was.
+        assert_eq!(repair(&archive).unwrap(), archive);
+
+        // `./a`'s header starts at 0 and `./b`'s at 124.
+        for &(offset, field) in [
+            (54, "00000009"),
+            (54, "00000001"),
+            (124 + 54, "00000100"),
+            (124 + 94, "00000002"),
+        ]
+        .iter()
+        {
+            let mut corrupt = archive.clone();
+            corrupt[offset..offset + 8].copy_from_slice(field.as_bytes());
+            assert!(read_contents(&corrupt).ok() != Some(expected.clone()));
+
+            let repaired = repair(&corrupt).unwrap();
+            assert_eq!(repaired, archive);
+            assert_eq!(read_contents(&repaired).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_raw_field() {
+        // Upper case and space padding aren't what the builder writes, but
+        // are still accepted.
+        let header = header(&[
+            (Field::Ino, "0000002A"),
+            (Field::Uid, "     1a4"),
+            (Field::Mode, "000081A4"),
+            (Field::Mtime, "5F3E2A10"),
+            (Field::NameSize, "00000004"),
+        ]);
+        let output = raw_entry(vec![], header.as_bytes(), b"./a\0", b"").unwrap();
+        let reader = Reader::new(output.as_slice()).unwrap();
+        let entry = reader.entry();
+        assert_eq!(entry.mode(), 0o100644);
+        assert_eq!(entry.uid(), 0x1a4);
+        assert_eq!(entry.raw_field(Field::Ino), Some(&b"0000002A"[..]));
+        assert_eq!(entry.raw_field(Field::Uid), Some(&b"     1a4"[..]));
+        assert_eq!(entry.raw_field(Field::Mode), Some(&b"000081A4"[..]));
+        assert_eq!(entry.raw_field(Field::Mtime), Some(&b"5F3E2A10"[..]));
+        assert_eq!(entry.raw_field(Field::NameSize), Some(&b"00000004"[..]));
+        assert_eq!(entry.raw_field(Field::Check), Some(&b"00000000"[..]));
+
+        assert_eq!(Entry::new("./a", 0o100644).raw_field(Field::Mode), None);
+
+        // The raw bytes don't affect comparisons.
+        let mut built = Entry::new("./a", 0o100644);
+        built.ino = 42;
+        built.uid = 0x1a4;
+        built.mtime = 0x5f3e_2a10;
+        assert_eq!(*entry, built);
+        let mut renamed = entry.clone();
+        renamed.set_name("./b");
+        built.set_name("./b");
+        assert_eq!(renamed, built);
+    }
+
+    #[test]
+    fn test_minimal_length() {
+        fn align(len: usize) -> usize {
+            (len + 3) & !3
+        }
+
+        let data: &[u8] = b"Hello, World";
+        let name = "./hello_world";
+
+        let b = Builder::new(name);
+        let mut writer = b.write(vec![], data.len() as u32);
+        copy(&mut Cursor::new(data), &mut writer).unwrap();
+        let output = trailer(writer.finish().unwrap()).unwrap();
+
+        // No padding is emitted after the trailer beyond its own alignment.
+        let entry = align(HEADER_LEN + name.len() + 1) + align(data.len());
+        let trailer = align(HEADER_LEN + TRAILER_NAME.len() + 1);
+        assert_eq!(output.len(), entry + trailer);
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    extern crate test;
+
+    use self::test::{black_box, Bencher};
+    use super::*;
+
+    /// The straightforward per-digit decode that `decode_hex8` replaces.
+    fn decode_hex8_naive(bytes: &[u8; 8]) -> io::Result<u32> {
+        let mut value = 0u32;
+        for &byte in bytes {
+            let digit = match byte {
+                b'0'..=b'9' => byte - b'0',
+                b'a'..=b'f' => byte - b'a' + 10,
+                b'A'..=b'F' => byte - b'A' + 10,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid hex u32 header field",
+                    ))
+                }
+            };
+            value = (value << 4) | u32::from(digit);
+        }
+        Ok(value)
+    }
+
+    const FIELDS: [&[u8; 8]; 4] = [b"000081a4", b"DEADBEEF", b"00000000", b"5f3e2a10"];
+
+    #[bench]
+    fn bench_decode_hex8(b: &mut Bencher) {
+        b.iter(|| {
+            for field in FIELDS.iter() {
+                black_box(decode_hex8(black_box(field)).unwrap());
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_decode_hex8_naive(b: &mut Bencher) {
+        b.iter(|| {
+            for field in FIELDS.iter() {
+                black_box(decode_hex8_naive(black_box(field)).unwrap());
+            }
+        });
+    }
 }
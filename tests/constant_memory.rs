@@ -0,0 +1,139 @@
+// Checks that reading an archive takes a bounded amount of memory, however
+// large the archive and the files in it are.
+
+extern crate cpio;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Wraps the system allocator to keep track of the peak number of bytes
+/// allocated at once.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+const ENTRIES: u32 = 64;
+const FILE_SIZE: u32 = 4 * 1024 * 1024 + 3;
+
+/// Generates an archive of `ENTRIES` files of `FILE_SIZE` bytes each on the
+/// fly, without ever holding more than one header in memory.
+struct HugeArchive {
+    next_entry: u32,
+    header: io::Cursor<Vec<u8>>,
+    data_remaining: u64,
+    padding_remaining: u64,
+}
+
+impl HugeArchive {
+    fn new() -> HugeArchive {
+        HugeArchive {
+            next_entry: 0,
+            header: io::Cursor::new(vec![]),
+            data_remaining: 0,
+            padding_remaining: 0,
+        }
+    }
+
+    /// Queues up the header of the next entry, or the trailer after the last.
+    fn next_header(&mut self) -> bool {
+        let header = if self.next_entry < ENTRIES {
+            // Without any data written, finishing only emits the header.
+            let name = format!("./file{}", self.next_entry);
+            self.data_remaining = u64::from(FILE_SIZE);
+            self.padding_remaining = u64::from((4 - FILE_SIZE % 4) % 4);
+            cpio::NewcBuilder::new(&name)
+                .mode(0o100644)
+                .write(vec![], FILE_SIZE)
+                .finish()
+                .unwrap()
+        } else if self.next_entry == ENTRIES {
+            cpio::newc::trailer(vec![]).unwrap()
+        } else {
+            return false;
+        };
+        self.next_entry += 1;
+        self.header = io::Cursor::new(header);
+        true
+    }
+}
+
+impl Read for HugeArchive {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.header.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            if self.data_remaining > 0 {
+                let n = buf.len().min(self.data_remaining as usize);
+                for byte in &mut buf[..n] {
+                    *byte = b'x';
+                }
+                self.data_remaining -= n as u64;
+                return Ok(n);
+            }
+            if self.padding_remaining > 0 {
+                let n = buf.len().min(self.padding_remaining as usize);
+                for byte in &mut buf[..n] {
+                    *byte = 0;
+                }
+                self.padding_remaining -= n as u64;
+                return Ok(n);
+            }
+            if !self.next_header() {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_constant_memory() {
+    let mut input = HugeArchive::new();
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    let mut entries = 0;
+    let mut total = 0;
+    loop {
+        let mut reader = cpio::NewcReader::new(input).unwrap();
+        if reader.entry().is_trailer() {
+            break;
+        }
+        assert_eq!(reader.entry().file_size(), FILE_SIZE);
+        // Stream some bodies through and leave the rest for `finish` to skip.
+        if entries % 2 == 0 {
+            total += io::copy(&mut reader, &mut io::sink()).unwrap();
+        }
+        entries += 1;
+        input = reader.finish().unwrap();
+    }
+    assert_eq!(entries, ENTRIES);
+    assert_eq!(total, u64::from(ENTRIES / 2) * u64::from(FILE_SIZE));
+
+    // Hundreds of megabytes went through, but only a few small allocations (entry
+    // names and generated headers) were ever live at once.
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+    assert!(peak < 16 * 1024, "peak allocation was {} bytes", peak);
+}